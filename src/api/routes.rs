@@ -60,10 +60,14 @@
 use axum::{
     routing::{get, post, delete},
     Router,
-    extract::{Path, State},
+    extract::{Path, Query, State},
+    extract::ws::{Message, WebSocket, WebSocketUpgrade},
+    response::sse::{Event, KeepAlive, Sse},
+    response::IntoResponse,
     Json,
     http::StatusCode,
 };
+use futures::{SinkExt, Stream, StreamExt};
 use serde::{Deserialize, Serialize};
 use std::sync::Arc;
 use tokio::sync::Mutex;
@@ -71,6 +75,12 @@ use tracing::error;
 use std::path::PathBuf;
 
 use crate::core::libvirt::LibvirtManager;
+use crate::core::migration::{MigrationManager, MigrationMode};
+use crate::core::snapshot::{CreateSnapshotRequest, SnapshotManager, SnapshotResponse};
+use crate::core::console::ConsoleManager;
+use crate::core::hotplug::HotplugMethod;
+use crate::core::resource_manager::ResourceManager;
+use crate::core::events::{EventBus, EventKind};
 use crate::core::vm::{VMStatus, VMConfig};
 use crate::gpu::device::{GPUManager, GPUDevice, GPUConfig};
 use crate::monitoring::metrics::{MetricsCollector, ResourceMetrics};
@@ -80,10 +90,25 @@ fn handle_error(err: impl std::fmt::Display) -> StatusCode {
     StatusCode::INTERNAL_SERVER_ERROR
 }
 
+/// Map a quota/accounting failure onto a client-facing status: an
+/// over-subscription is a `409 CONFLICT`, a malformed request a `400`.
+fn quota_error(err: crate::core::errors::GpuShareError) -> StatusCode {
+    use crate::core::errors::GpuShareError;
+    error!("Quota check failed: {}", err);
+    match err {
+        GpuShareError::QuotaExceeded(_) => StatusCode::CONFLICT,
+        GpuShareError::InvalidConfig(_) => StatusCode::BAD_REQUEST,
+        _ => StatusCode::INTERNAL_SERVER_ERROR,
+    }
+}
+
 pub struct AppState {
     pub libvirt: Arc<Mutex<LibvirtManager>>,
     pub gpu_manager: Arc<Mutex<GPUManager>>,
     pub metrics: Arc<Mutex<MetricsCollector>>,
+    pub consoles: Arc<Mutex<ConsoleManager>>,
+    pub resources: Arc<Mutex<ResourceManager>>,
+    pub events: Arc<EventBus>,
 }
 
 #[derive(Debug, Deserialize)]
@@ -93,6 +118,8 @@ pub struct CreateVMRequest {
     pub memory_mb: u64,
     pub gpu_required: bool,
     pub disk_size_gb: Option<u64>,
+    #[serde(default = "default_user")]
+    pub user: String,
 }
 
 #[derive(Debug, Serialize)]
@@ -109,6 +136,16 @@ pub struct VMResponse {
 #[derive(Debug, Deserialize)]
 pub struct AttachGPURequest {
     pub gpu_id: String,
+    /// Requested slice of the device in `(0.0, 1.0]`; defaults to the whole GPU.
+    #[serde(default = "default_fraction")]
+    pub fraction: f64,
+    /// Owner whose GPU quota the reservation is charged against.
+    #[serde(default = "default_user")]
+    pub user: String,
+}
+
+fn default_fraction() -> f64 {
+    1.0
 }
 
 pub fn create_router(state: Arc<AppState>) -> Router {
@@ -122,6 +159,15 @@ pub fn create_router(state: Arc<AppState>) -> Router {
         .route("/api/v1/gpus", get(list_gpus))
         .route("/api/v1/vms/:id/attach_gpu", post(attach_gpu))
         .route("/api/v1/metrics/:id", get(get_metrics))
+        .route("/api/v1/vms/:id/migrate/send", post(migrate_send))
+        .route("/api/v1/vms/:id/migrate/receive", post(migrate_receive))
+        .route("/api/v1/vms/:id/snapshots", post(create_snapshot))
+        .route("/api/v1/vms/:id/snapshots", get(list_snapshots))
+        .route("/api/v1/vms/:id/snapshots/:snap/revert", post(revert_snapshot))
+        .route("/api/v1/vms/:id/snapshots/:snap", delete(delete_snapshot))
+        .route("/api/v1/vms/:id/console", get(console_ws))
+        .route("/api/v1/vms/:id/resize", post(resize_vm))
+        .route("/api/v1/events", get(events_stream))
         .with_state(state)
 }
 
@@ -139,13 +185,42 @@ async fn create_vm(
         disk_path: PathBuf::from(format!("/var/lib/gpu-share/images/{}.qcow2", params.name)),
         disk_size_gb: params.disk_size_gb.unwrap_or(20),
     };
-    
+
+    // Reject over-subscription before we spend anything creating the domain.
+    let mut resources = state.resources.lock().await;
+    if let Err(e) = resources.check_quota(&params.user, None, &config) {
+        state.events.emit(&params.name, EventKind::QuotaRejected { reason: e.to_string() });
+        return Err(quota_error(e));
+    }
+
     let vm = libvirt.create_vm(&config).await
         .map_err(handle_error)?;
 
     let vm_id = vm.get_uuid_string()
         .map_err(handle_error)?;
 
+    // Record the reservation now that the domain exists.
+    resources.reserve(&params.user, &vm_id, &config).map_err(quota_error)?;
+    drop(resources);
+
+    state.events.emit(&vm_id, EventKind::VmCreated);
+
+    // Allocate the VM's serial console and wire its subordinate PTY into the
+    // domain now, so the guest's console is available over
+    // `GET /api/v1/vms/:id/console` as soon as it boots. Console setup is
+    // auxiliary: log and carry on rather than failing the create.
+    {
+        let mut consoles = state.consoles.lock().await;
+        match consoles.ensure_console(&vm_id) {
+            Ok(sub_path) => {
+                if let Err(e) = libvirt.attach_serial_console(&vm_id, &sub_path) {
+                    error!("failed to attach serial console for {}: {}", vm_id, e);
+                }
+            }
+            Err(e) => error!("failed to allocate console for {}: {}", vm_id, e),
+        }
+    }
+
     let mut metrics = state.metrics.lock().await;
     if let Err(e) = metrics.start_collection(vm_id.clone(), vm.clone()).await {
         error!("Failed to start metrics collection: {}", e);
@@ -230,6 +305,8 @@ async fn start_vm(
         .await
         .map_err(handle_error)?;
 
+    state.events.emit(&id, EventKind::VmBooted);
+
     Ok(StatusCode::OK)
 }
 
@@ -244,6 +321,8 @@ async fn stop_vm(
         .await
         .map_err(handle_error)?;
 
+    state.events.emit(&id, EventKind::VmStopped);
+
     Ok(StatusCode::OK)
 }
 
@@ -258,19 +337,46 @@ async fn delete_vm(
         .await
         .map_err(handle_error)?;
 
+    // Return the VM's vCPU/memory/disk and GPU reservations to the user's pool.
+    state.resources.lock().await.release(&id);
+
+    state.events.emit(&id, EventKind::VmDeleted);
+
     Ok(StatusCode::OK)
 }
 
+/// A discovered GPU annotated with how much of it is already committed.
+#[derive(Debug, Serialize)]
+pub struct GPUListEntry {
+    #[serde(flatten)]
+    pub device: GPUDevice,
+    pub allocated_fraction: f64,
+    pub free_fraction: f64,
+}
+
 #[axum::debug_handler]
 async fn list_gpus(
     State(state): State<Arc<AppState>>,
-) -> Result<Json<Vec<GPUDevice>>, StatusCode> {
+) -> Result<Json<Vec<GPUListEntry>>, StatusCode> {
     let mut gpu_manager = state.gpu_manager.lock().await;
-    
+
     let gpus = gpu_manager.discover_gpus()
         .map_err(handle_error)?;
 
-    Ok(Json(gpus))
+    let ids: Vec<String> = gpus.iter().map(|g| g.id.clone()).collect();
+    let allocations = state.resources.lock().await.gpu_allocations(&ids);
+
+    let entries = gpus
+        .into_iter()
+        .zip(allocations)
+        .map(|(device, alloc)| GPUListEntry {
+            device,
+            allocated_fraction: alloc.allocated_fraction,
+            free_fraction: alloc.free_fraction,
+        })
+        .collect();
+
+    Ok(Json(entries))
 }
 
 #[axum::debug_handler]
@@ -293,12 +399,329 @@ async fn attach_gpu(
             .ok_or(StatusCode::BAD_REQUEST)?,
     };
 
-    gpu_manager.attach_gpu_to_vm(&domain, &gpu_config).await
+    // Reserve the requested fraction first so an over-subscribed device or user
+    // is rejected before we touch libvirt; roll it back if the attach fails.
+    {
+        let mut resources = state.resources.lock().await;
+        if let Err(e) = resources.reserve_gpu(&request.user, &id, &gpu_id, request.fraction) {
+            state.events.emit(&id, EventKind::QuotaRejected { reason: e.to_string() });
+            return Err(quota_error(e));
+        }
+    }
+
+    if let Err(e) = gpu_manager.attach_gpu_to_vm(&domain, &gpu_config).await {
+        state.resources.lock().await.release_gpu(&id);
+        return Err(handle_error(e));
+    }
+
+    state.events.emit(
+        &id,
+        EventKind::GpuAttached { gpu_id, fraction: request.fraction },
+    );
+
+    Ok(StatusCode::OK)
+}
+
+#[derive(Debug, Deserialize)]
+pub struct MigrateRequest {
+    /// `"local"` for same-host FD handoff, `"cross_host"` to stream RAM.
+    pub mode: String,
+    /// Unix socket path (local mode) or `host:port` (cross-host) of the target.
+    pub endpoint: String,
+}
+
+#[axum::debug_handler]
+async fn migrate_send(
+    State(state): State<Arc<AppState>>,
+    Path(id): Path<String>,
+    Json(request): Json<MigrateRequest>,
+) -> Result<StatusCode, StatusCode> {
+    let mode = match request.mode.as_str() {
+        "local" => MigrationMode::LocalFdHandoff,
+        "cross_host" => MigrationMode::CrossHostStream,
+        _ => return Err(StatusCode::BAD_REQUEST),
+    };
+
+    let libvirt = state.libvirt.lock().await;
+    let manager = MigrationManager::new(libvirt.clone());
+    drop(libvirt);
+
+    state.events.emit(&id, EventKind::MigrationStarted { mode: request.mode.clone() });
+
+    manager.send(&id, mode, &request.endpoint).await
+        .map_err(handle_error)?;
+
+    state.events.emit(&id, EventKind::MigrationCompleted);
+
+    Ok(StatusCode::OK)
+}
+
+#[axum::debug_handler]
+async fn migrate_receive(
+    State(state): State<Arc<AppState>>,
+    Path(_id): Path<String>,
+    Json(request): Json<MigrateRequest>,
+) -> Result<StatusCode, StatusCode> {
+    let mode = match request.mode.as_str() {
+        "local" => MigrationMode::LocalFdHandoff,
+        "cross_host" => MigrationMode::CrossHostStream,
+        _ => return Err(StatusCode::BAD_REQUEST),
+    };
+
+    let libvirt = state.libvirt.lock().await;
+    let manager = MigrationManager::new(libvirt.clone());
+    drop(libvirt);
+
+    let mut gpu_manager = state.gpu_manager.lock().await;
+    match mode {
+        MigrationMode::LocalFdHandoff => {
+            let listener = crate::core::migration::serve_local(request.endpoint.into())
+                .await
+                .map_err(handle_error)?;
+            let (stream, _addr) = listener.accept().await.map_err(handle_error)?;
+            manager.receive_local(stream, &mut gpu_manager).await
+                .map_err(handle_error)?;
+        }
+        MigrationMode::CrossHostStream => {
+            let listener = crate::core::migration::serve_cross_host(&request.endpoint)
+                .await
+                .map_err(handle_error)?;
+            let (stream, _addr) = listener.accept().await.map_err(handle_error)?;
+            manager.receive_cross_host(stream, &mut gpu_manager).await
+                .map_err(handle_error)?;
+        }
+    }
+
+    Ok(StatusCode::OK)
+}
+
+#[axum::debug_handler]
+async fn create_snapshot(
+    State(state): State<Arc<AppState>>,
+    Path(id): Path<String>,
+    Json(request): Json<CreateSnapshotRequest>,
+) -> Result<Json<SnapshotResponse>, StatusCode> {
+    let libvirt = state.libvirt.lock().await;
+    let manager = SnapshotManager::new(libvirt.clone());
+    drop(libvirt);
+
+    let snapshot = manager.create(&id, &request)
+        .map_err(handle_error)?;
+
+    state.events.emit(&id, EventKind::SnapshotTaken { name: snapshot.name.clone() });
+
+    Ok(Json(snapshot))
+}
+
+#[axum::debug_handler]
+async fn list_snapshots(
+    State(state): State<Arc<AppState>>,
+    Path(id): Path<String>,
+) -> Result<Json<Vec<SnapshotResponse>>, StatusCode> {
+    let libvirt = state.libvirt.lock().await;
+    let manager = SnapshotManager::new(libvirt.clone());
+    drop(libvirt);
+
+    let snapshots = manager.list(&id)
+        .map_err(handle_error)?;
+
+    Ok(Json(snapshots))
+}
+
+#[axum::debug_handler]
+async fn revert_snapshot(
+    State(state): State<Arc<AppState>>,
+    Path((id, snap)): Path<(String, String)>,
+) -> Result<StatusCode, StatusCode> {
+    let libvirt = state.libvirt.lock().await;
+    let manager = SnapshotManager::new(libvirt.clone());
+    drop(libvirt);
+
+    manager.revert(&id, &snap)
         .map_err(handle_error)?;
 
     Ok(StatusCode::OK)
 }
 
+#[axum::debug_handler]
+async fn delete_snapshot(
+    State(state): State<Arc<AppState>>,
+    Path((id, snap)): Path<(String, String)>,
+) -> Result<StatusCode, StatusCode> {
+    let libvirt = state.libvirt.lock().await;
+    let manager = SnapshotManager::new(libvirt.clone());
+    drop(libvirt);
+
+    manager.delete(&id, &snap)
+        .map_err(handle_error)?;
+
+    Ok(StatusCode::OK)
+}
+
+#[axum::debug_handler]
+async fn console_ws(
+    State(state): State<Arc<AppState>>,
+    Path(id): Path<String>,
+    ws: WebSocketUpgrade,
+) -> Result<impl IntoResponse, StatusCode> {
+    // Allocate the console (idempotent) and attach this client before the
+    // upgrade so a failure surfaces as an HTTP error rather than a dead socket.
+    let mut consoles = state.consoles.lock().await;
+    consoles.ensure_console(&id).map_err(handle_error)?;
+    let handle = consoles.attach(&id).map_err(handle_error)?;
+    drop(consoles);
+
+    Ok(ws.on_upgrade(move |socket| console_bridge(socket, handle)))
+}
+
+/// Route bytes between the WebSocket and the console's primary fd: output
+/// (including the replayed history) flows to the client, client input flows in.
+async fn console_bridge(socket: WebSocket, mut handle: crate::core::console::ConsoleHandle) {
+    let (mut sink, mut stream) = socket.split();
+
+    // Replay recent output so a late joiner sees boot/log context.
+    let replay = handle.replay();
+    if !replay.is_empty() && sink.send(Message::Binary(replay)).await.is_err() {
+        return;
+    }
+
+    loop {
+        tokio::select! {
+            out = handle.recv() => match out {
+                Some(bytes) => {
+                    if sink.send(Message::Binary(bytes.to_vec())).await.is_err() {
+                        break;
+                    }
+                }
+                None => break,
+            },
+            msg = stream.next() => match msg {
+                Some(Ok(Message::Binary(data))) => {
+                    if handle.write_input(&data).is_err() {
+                        break;
+                    }
+                }
+                Some(Ok(Message::Text(text))) => {
+                    if handle.write_input(text.as_bytes()).is_err() {
+                        break;
+                    }
+                }
+                Some(Ok(Message::Close(_))) | None => break,
+                _ => {}
+            },
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+pub struct ResizeRequest {
+    /// New online vCPU count; omit to leave vCPUs unchanged.
+    pub vcpus: Option<u32>,
+    /// New target memory in MiB; omit to leave memory unchanged.
+    pub memory_mb: Option<u64>,
+    /// Owner whose quota the new totals are checked against.
+    #[serde(default = "default_user")]
+    pub user: String,
+    /// Expected hotplug mechanism. vCPUs always use CPU hotplug and memory the
+    /// balloon; a method that does not match the requested changes is rejected.
+    pub method: Option<HotplugMethod>,
+}
+
+fn default_user() -> String {
+    "default".to_string()
+}
+
+#[axum::debug_handler]
+async fn resize_vm(
+    State(state): State<Arc<AppState>>,
+    Path(id): Path<String>,
+    Json(request): Json<ResizeRequest>,
+) -> Result<Json<VMResponse>, StatusCode> {
+    let libvirt = state.libvirt.lock().await;
+    let domain = libvirt.lookup_domain(&id).map_err(handle_error)?;
+    let info = domain.get_info().map_err(handle_error)?;
+    let name = domain.get_name().map_err(handle_error)?;
+
+    let target_vcpus = request.vcpus.unwrap_or(info.nr_virt_cpu);
+    let target_memory_mb = request.memory_mb.unwrap_or(info.memory / 1024);
+
+    // The new totals must fit within the user's quota before we touch the VM.
+    let prospective = VMConfig {
+        name: name.clone(),
+        memory_kb: target_memory_mb * 1024,
+        vcpus: target_vcpus,
+        disk_path: PathBuf::from(format!("/var/lib/gpu-share/images/{name}.qcow2")),
+        disk_size_gb: 0,
+    };
+    if let Err(e) = state
+        .resources
+        .lock()
+        .await
+        .check_quota(&request.user, Some(&id), &prospective)
+    {
+        state.events.emit(&id, EventKind::QuotaRejected { reason: e.to_string() });
+        return Err(quota_error(e));
+    }
+
+    // Refuse to shrink memory below what the guest has already committed.
+    if let Some(memory_mb) = request.memory_mb {
+        let metrics = state.metrics.lock().await;
+        let committed_mb = metrics.committed_memory_kb(&id).map_err(handle_error)? / 1024;
+        if memory_mb < committed_mb {
+            error!(
+                "resize rejected: target {} MiB below committed {} MiB",
+                memory_mb, committed_mb
+            );
+            return Err(StatusCode::BAD_REQUEST);
+        }
+    }
+
+    // vCPUs always go through CPU hotplug and memory through the balloon, so an
+    // explicit `method` only makes sense when exactly the matching resource is
+    // being changed. Reject an incompatible combination up front rather than
+    // applying one change and failing on the next, which would leave the VM
+    // partially resized.
+    if let Some(method) = request.method {
+        let compatible = match method {
+            HotplugMethod::CpuHotplug => request.vcpus.is_some() && request.memory_mb.is_none(),
+            HotplugMethod::MemoryBalloon => request.memory_mb.is_some() && request.vcpus.is_none(),
+        };
+        if !compatible {
+            error!("resize rejected: method {:?} does not match the requested changes", method);
+            return Err(StatusCode::BAD_REQUEST);
+        }
+    }
+
+    // All requested changes are known-valid; apply them.
+    if let Some(vcpus) = request.vcpus {
+        libvirt.set_vcpus_live(&id, vcpus).await.map_err(handle_error)?;
+    }
+    if let Some(memory_mb) = request.memory_mb {
+        libvirt.set_memory_live(&id, memory_mb * 1024).await.map_err(handle_error)?;
+    }
+
+    // Persist the new totals so the user's committed usage tracks the resize;
+    // otherwise the stored reservation would keep the VM's original sizing and
+    // let later requests under-count this user.
+    state
+        .resources
+        .lock()
+        .await
+        .update_resize(&request.user, &id, target_vcpus, target_memory_mb * 1024);
+
+    Ok(Json(VMResponse {
+        id,
+        name,
+        status: VMStatus::from(info.state),
+        gpu_attached: domain.get_xml_desc(0)
+            .map(|xml| xml.contains("<hostdev"))
+            .unwrap_or(false),
+        memory_mb: target_memory_mb,
+        cpu_cores: target_vcpus,
+        disk_size_gb: 0,
+    }))
+}
+
 #[axum::debug_handler]
 async fn get_metrics(
     State(state): State<Arc<AppState>>,
@@ -310,4 +733,49 @@ async fn get_metrics(
         .map_err(handle_error)?;
 
     Ok(Json(vm_metrics))
+}
+
+#[derive(Debug, Deserialize)]
+pub struct EventQuery {
+    /// Restrict the stream (and the replayed history) to a single VM.
+    pub vm_id: Option<String>,
+}
+
+/// Stream VM lifecycle events as Server-Sent Events. The most recent events are
+/// replayed first so a dashboard can reconstruct current state on connect, then
+/// live events follow until the client disconnects.
+#[axum::debug_handler]
+async fn events_stream(
+    State(state): State<Arc<AppState>>,
+    Query(params): Query<EventQuery>,
+) -> Sse<impl Stream<Item = Result<Event, std::convert::Infallible>>> {
+    let filter = params.vm_id;
+    let receiver = state.events.subscribe();
+    let replay = state.events.recent(filter.as_deref());
+
+    let history = futures::stream::iter(replay);
+    let live = futures::stream::unfold(receiver, |mut rx| async move {
+        loop {
+            match rx.recv().await {
+                Ok(event) => return Some((event, rx)),
+                // A lagged subscriber simply skips the dropped events.
+                Err(tokio::sync::broadcast::error::RecvError::Lagged(_)) => continue,
+                Err(tokio::sync::broadcast::error::RecvError::Closed) => return None,
+            }
+        }
+    });
+
+    let stream = history.chain(live).filter_map(move |event| {
+        let filter = filter.clone();
+        async move {
+            if let Some(ref id) = filter {
+                if event.vm_id != *id {
+                    return None;
+                }
+            }
+            Event::default().json_data(&event).ok().map(Ok)
+        }
+    });
+
+    Sse::new(stream).keep_alive(KeepAlive::default())
 }
\ No newline at end of file
@@ -0,0 +1,273 @@
+//! Per-VM serial console.
+//!
+//! Each domain gets a PTY pair allocated on first attach. The subordinate side
+//! is configured in raw mode and handed to the VMM as the domain's serial
+//! device; we deliberately keep our own copy of the subordinate fd open for the
+//! lifetime of the console so that clients can disconnect and reconnect without
+//! the VMM ever seeing an `EIO`/`EPIPE` on a write into a PTY with no reader.
+//!
+//! The primary side carries traffic both ways: bytes written by a client flow
+//! in, console output flows out. Output is fanned out to every attached client
+//! through a [`broadcast`] channel, so several viewers can watch the same
+//! console at once, and a small ring buffer replays the last few kilobytes to a
+//! freshly attached client so late joiners still see recent boot/log output.
+
+use std::collections::HashMap;
+use std::os::unix::io::{AsRawFd, OwnedFd, RawFd};
+use std::sync::{Arc, Mutex};
+
+use bytes::Bytes;
+use thiserror::Error;
+use tokio::sync::broadcast;
+use tracing::{info, warn};
+
+use crate::core::errors::GpuShareError;
+use crate::core::libvirt::LibvirtManager;
+
+/// How many bytes of recent output to replay to a newly attached client.
+const REPLAY_BYTES: usize = 16 * 1024;
+
+/// Fan-out channel depth; slow clients that fall this far behind are lagged.
+const BROADCAST_DEPTH: usize = 1024;
+
+#[derive(Debug, Error)]
+pub enum ConsoleError {
+    #[error("io error: {0}")]
+    Io(#[from] std::io::Error),
+    #[error("failed to allocate pty: {0}")]
+    Pty(String),
+    #[error("no console attached for vm {0}")]
+    NotAttached(String),
+}
+
+type Result<T> = std::result::Result<T, ConsoleError>;
+
+/// A ring buffer holding the most recent console output.
+struct ReplayBuffer {
+    buf: Vec<u8>,
+    cap: usize,
+}
+
+impl ReplayBuffer {
+    fn new(cap: usize) -> Self {
+        Self { buf: Vec::with_capacity(cap), cap }
+    }
+
+    fn push(&mut self, bytes: &[u8]) {
+        self.buf.extend_from_slice(bytes);
+        if self.buf.len() > self.cap {
+            let overflow = self.buf.len() - self.cap;
+            self.buf.drain(0..overflow);
+        }
+    }
+
+    fn snapshot(&self) -> Vec<u8> {
+        self.buf.clone()
+    }
+}
+
+/// A single domain's console: the PTY fds plus the output fan-out.
+struct Console {
+    /// Primary (a.k.a. master) fd: input goes in, output comes out.
+    primary: Arc<OwnedFd>,
+    /// Subordinate fd handed to the VMM. Kept open so writes never get `EIO`
+    /// when no client is attached; never read from directly here.
+    _subordinate: OwnedFd,
+    /// Broadcast source for console output.
+    output: broadcast::Sender<Bytes>,
+    /// Recent output replayed to late joiners.
+    replay: Arc<Mutex<ReplayBuffer>>,
+}
+
+/// A client's handle onto a console: a subscription to output plus the fd to
+/// write input into.
+pub struct ConsoleHandle {
+    primary: Arc<OwnedFd>,
+    output: broadcast::Receiver<Bytes>,
+    replay: Vec<u8>,
+}
+
+impl ConsoleHandle {
+    /// Bytes buffered before this client attached, oldest first. Drain this
+    /// before reading live output so the client sees recent history in order.
+    pub fn replay(&mut self) -> Vec<u8> {
+        std::mem::take(&mut self.replay)
+    }
+
+    /// Await the next chunk of live console output.
+    pub async fn recv(&mut self) -> Option<Bytes> {
+        loop {
+            match self.output.recv().await {
+                Ok(bytes) => return Some(bytes),
+                Err(broadcast::error::RecvError::Lagged(n)) => {
+                    warn!(dropped = n, "console client lagged, skipping output");
+                    continue;
+                }
+                Err(broadcast::error::RecvError::Closed) => return None,
+            }
+        }
+    }
+
+    /// Write client input into the console's primary fd.
+    pub fn write_input(&self, data: &[u8]) -> Result<()> {
+        use nix::unistd::write;
+        let mut off = 0;
+        while off < data.len() {
+            let n = write(self.primary.as_raw_fd(), &data[off..])
+                .map_err(|e| ConsoleError::Io(std::io::Error::from_raw_os_error(e as i32)))?;
+            off += n;
+        }
+        Ok(())
+    }
+}
+
+/// Owns every VM's console and allocates PTYs on demand.
+#[derive(Default)]
+pub struct ConsoleManager {
+    consoles: HashMap<String, Console>,
+}
+
+impl ConsoleManager {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Path of the subordinate PTY for `vm_id`, allocating the console if this
+    /// is the first attach. The returned path is what the VMM should open as
+    /// the domain's serial device.
+    pub fn ensure_console(&mut self, vm_id: &str) -> Result<String> {
+        if !self.consoles.contains_key(vm_id) {
+            let (console, sub_path) = Console::open(vm_id)?;
+            self.consoles.insert(vm_id.to_string(), console);
+            info!(vm_id, sub = %sub_path, "allocated console pty");
+            return Ok(sub_path);
+        }
+        // Already open; recompute the subordinate path for reporting.
+        let console = &self.consoles[vm_id];
+        ptsname(console.primary.as_raw_fd())
+    }
+
+    /// Attach a new client to `vm_id`'s console, subscribing it to output and
+    /// capturing the current replay buffer.
+    pub fn attach(&mut self, vm_id: &str) -> Result<ConsoleHandle> {
+        let console = self
+            .consoles
+            .get(vm_id)
+            .ok_or_else(|| ConsoleError::NotAttached(vm_id.to_string()))?;
+        Ok(ConsoleHandle {
+            primary: console.primary.clone(),
+            output: console.output.subscribe(),
+            replay: console.replay.lock().unwrap().snapshot(),
+        })
+    }
+}
+
+impl LibvirtManager {
+    /// Wire the subordinate side of `vm_id`'s console PTY (as returned by
+    /// [`ConsoleManager::ensure_console`]) into the domain as a `pty` serial
+    /// device, so the guest's serial output flows out over the console's
+    /// primary fd. Applied to the persistent config so it survives a reboot.
+    pub fn attach_serial_console(&self, vm_id: &str, sub_path: &str) -> std::result::Result<(), GpuShareError> {
+        use virt::sys::VIR_DOMAIN_AFFECT_CONFIG;
+
+        let domain = self.lookup_domain(vm_id)?;
+        let xml = format!(
+            "<serial type='pty'><source path='{sub_path}'/><target port='0'/></serial>"
+        );
+        domain
+            .attach_device_flags(&xml, VIR_DOMAIN_AFFECT_CONFIG)
+            .map_err(|e| GpuShareError::Libvirt(e.to_string()))?;
+        info!(vm_id, serial = %sub_path, "attached console pty to domain serial device");
+        Ok(())
+    }
+}
+
+impl Console {
+    fn open(vm_id: &str) -> Result<(Self, String)> {
+        use nix::pty::{openpty, OpenptyResult};
+
+        let OpenptyResult { master, slave } =
+            openpty(None, None).map_err(|e| ConsoleError::Pty(e.to_string()))?;
+        set_raw(slave.as_raw_fd())?;
+        let sub_path = ptsname(master.as_raw_fd())?;
+
+        let primary = Arc::new(master);
+        let (output, _rx) = broadcast::channel(BROADCAST_DEPTH);
+        let replay = Arc::new(Mutex::new(ReplayBuffer::new(REPLAY_BYTES)));
+
+        spawn_reader(vm_id.to_string(), primary.clone(), output.clone(), replay.clone());
+
+        Ok((
+            Self { primary, _subordinate: slave, output, replay },
+            sub_path,
+        ))
+    }
+}
+
+/// Pump output from the primary fd into the broadcast channel and ring buffer.
+fn spawn_reader(
+    vm_id: String,
+    primary: Arc<OwnedFd>,
+    output: broadcast::Sender<Bytes>,
+    replay: Arc<Mutex<ReplayBuffer>>,
+) {
+    tokio::task::spawn_blocking(move || {
+        use nix::unistd::read;
+        let mut buf = [0u8; 4096];
+        loop {
+            match read(primary.as_raw_fd(), &mut buf) {
+                Ok(0) => break,
+                Ok(n) => {
+                    let chunk = &buf[..n];
+                    replay.lock().unwrap().push(chunk);
+                    // A send error only means there are currently no clients;
+                    // output is still captured in the replay buffer.
+                    let _ = output.send(Bytes::copy_from_slice(chunk));
+                }
+                Err(nix::errno::Errno::EINTR) => continue,
+                Err(e) => {
+                    warn!(vm_id, error = %e, "console reader stopped");
+                    break;
+                }
+            }
+        }
+    });
+}
+
+/// Put a PTY fd into raw mode so the guest's serial bytes pass through untouched.
+fn set_raw(fd: RawFd) -> Result<()> {
+    use nix::sys::termios::{cfmakeraw, tcgetattr, tcsetattr, SetArg};
+    let mut termios = tcgetattr(fd).map_err(|e| ConsoleError::Pty(e.to_string()))?;
+    cfmakeraw(&mut termios);
+    tcsetattr(fd, SetArg::TCSANOW, &termios).map_err(|e| ConsoleError::Pty(e.to_string()))?;
+    Ok(())
+}
+
+fn ptsname(master: RawFd) -> Result<String> {
+    // Safe: we own the master fd for the duration of the call.
+    let name = unsafe { nix::pty::ptsname(&master) }
+        .map_err(|e| ConsoleError::Pty(e.to_string()))?;
+    Ok(name)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn replay_buffer_keeps_only_the_most_recent_bytes() {
+        let mut rb = ReplayBuffer::new(4);
+        rb.push(b"ab");
+        assert_eq!(rb.snapshot(), b"ab");
+        // Overflowing the cap evicts the oldest bytes, keeping the tail.
+        rb.push(b"cdef");
+        assert_eq!(rb.snapshot(), b"cdef");
+    }
+
+    #[test]
+    fn replay_buffer_below_capacity_retains_everything() {
+        let mut rb = ReplayBuffer::new(16);
+        rb.push(b"boot log");
+        assert_eq!(rb.snapshot(), b"boot log");
+    }
+}
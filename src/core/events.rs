@@ -0,0 +1,117 @@
+//! Structured VM lifecycle events and their fan-out to subscribers.
+//!
+//! Every interesting transition a handler drives — a VM being created, booted,
+//! migrated, snapshotted, or rejected by quota — is published as a [`VmEvent`]
+//! onto a process-wide [`EventBus`]. The bus keeps the last [`EVENT_HISTORY`]
+//! events in a ring so a freshly connected SSE client can replay recent state,
+//! and broadcasts new events to every live subscriber. The `MetricsCollector`
+//! is handed a clone of the bus and publishes [`EventKind::ThresholdCrossed`]
+//! when a VM crosses a configured resource limit (e.g. GPU memory above a
+//! threshold).
+
+use std::collections::VecDeque;
+use std::sync::Mutex;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use serde::Serialize;
+use tokio::sync::broadcast;
+
+/// Number of recent events retained for replay to new subscribers.
+pub const EVENT_HISTORY: usize = 256;
+
+/// Capacity of the broadcast channel; slow subscribers lag rather than block.
+const CHANNEL_CAPACITY: usize = 1024;
+
+/// What happened to a VM. Serialized with an internal `kind` tag so clients can
+/// switch on the event type while still seeing the type-specific fields.
+#[derive(Debug, Clone, Serialize)]
+#[serde(tag = "kind")]
+pub enum EventKind {
+    VmCreated,
+    VmBooted,
+    VmStopped,
+    VmDeleted,
+    GpuAttached { gpu_id: String, fraction: f64 },
+    MigrationStarted { mode: String },
+    MigrationCompleted,
+    SnapshotTaken { name: String },
+    QuotaRejected { reason: String },
+    /// A monitored metric crossed its configured limit, published by the
+    /// `MetricsCollector` (e.g. GPU memory above a threshold).
+    ThresholdCrossed { metric: String, value: f64, limit: f64 },
+}
+
+/// A lifecycle event tagged with its VM and a wall-clock timestamp.
+#[derive(Debug, Clone, Serialize)]
+pub struct VmEvent {
+    pub vm_id: String,
+    /// Milliseconds since the Unix epoch at which the event was published.
+    pub timestamp_ms: u64,
+    #[serde(flatten)]
+    pub kind: EventKind,
+}
+
+/// Broadcasts lifecycle events to all subscribers and replays recent history.
+#[derive(Debug)]
+pub struct EventBus {
+    sender: broadcast::Sender<VmEvent>,
+    history: Mutex<VecDeque<VmEvent>>,
+}
+
+impl Default for EventBus {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl EventBus {
+    pub fn new() -> Self {
+        let (sender, _) = broadcast::channel(CHANNEL_CAPACITY);
+        Self {
+            sender,
+            history: Mutex::new(VecDeque::with_capacity(EVENT_HISTORY)),
+        }
+    }
+
+    /// Publish `kind` for `vm_id`, stamping it with the current time. Recording
+    /// it in history and reaching zero subscribers are both non-fatal.
+    pub fn emit(&self, vm_id: impl Into<String>, kind: EventKind) {
+        let event = VmEvent {
+            vm_id: vm_id.into(),
+            timestamp_ms: now_ms(),
+            kind,
+        };
+        if let Ok(mut history) = self.history.lock() {
+            if history.len() == EVENT_HISTORY {
+                history.pop_front();
+            }
+            history.push_back(event.clone());
+        }
+        let _ = self.sender.send(event);
+    }
+
+    /// Subscribe to events published from this point on.
+    pub fn subscribe(&self) -> broadcast::Receiver<VmEvent> {
+        self.sender.subscribe()
+    }
+
+    /// The retained recent events, oldest first, optionally filtered to one VM.
+    pub fn recent(&self, vm_id: Option<&str>) -> Vec<VmEvent> {
+        let history = match self.history.lock() {
+            Ok(history) => history,
+            Err(poisoned) => poisoned.into_inner(),
+        };
+        history
+            .iter()
+            .filter(|e| vm_id.is_none_or(|id| e.vm_id == id))
+            .cloned()
+            .collect()
+    }
+}
+
+fn now_ms() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_millis() as u64)
+        .unwrap_or(0)
+}
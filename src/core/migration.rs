@@ -0,0 +1,478 @@
+//! Live VM migration.
+//!
+//! Moving a running domain to another host in the cluster comes in two
+//! flavours. The general case is a *cross-host* migration: we serialize the
+//! [`VMConfig`], the libvirt domain XML, and the GPU/IOMMU assignment, stream
+//! the guest's RAM over a socket, and rebuild the domain on the target.
+//!
+//! The interesting case is *local mode*. When the source and destination VMMs
+//! share a host (same kernel namespace), copying guest RAM is pure waste: the
+//! pages are already resident and both processes can map the same physical
+//! memory. Instead of copying, we hand the memory-region file descriptors to
+//! the target over a Unix socket using `SCM_RIGHTS` ancillary messages, tagging
+//! each FD with the memory slot index it backs. The receiver installs those FDs
+//! into its own address space and resumes the domain. A multi-second copy
+//! collapses into a sub-100ms descriptor handoff.
+//!
+//! The wire protocol is a small framed sequence with explicit acks:
+//!
+//! ```text
+//! Config  ->  <- ConfigAck
+//! MemoryRegions{slot,size,fd}...  ->  <- RegionsAck
+//! State  ->  <- StateAck
+//! Complete  ->  <- CompleteAck
+//! ```
+//!
+//! Before the receiver acknowledges the config it validates that every GPU
+//! passthrough device named in the config is actually free in its own IOMMU
+//! groups; if not, it aborts the migration cleanly and the source keeps the
+//! domain running.
+
+use std::os::unix::io::RawFd;
+use std::path::PathBuf;
+
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpStream, UnixStream};
+use tracing::{info, warn};
+
+use crate::core::libvirt::LibvirtManager;
+use crate::core::vm::VMConfig;
+use crate::gpu::device::GPUManager;
+
+/// How a migration should move guest memory.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum MigrationMode {
+    /// Source and destination share a host: pass memory-region FDs via
+    /// `SCM_RIGHTS` instead of copying RAM.
+    LocalFdHandoff,
+    /// Source and destination are different hosts: stream guest RAM over the
+    /// network.
+    CrossHostStream,
+}
+
+/// A single guest memory region to be transferred.
+///
+/// For local-mode migrations `fd` is the raw descriptor backing the slot and is
+/// sent out-of-band as ancillary data; for cross-host migrations the region's
+/// `size` bytes are streamed inline and `fd` is ignored on the wire.
+#[derive(Debug, Clone)]
+pub struct MemoryRegion {
+    /// Index of the KVM memory slot this region maps.
+    pub slot: u32,
+    /// Size of the region in bytes.
+    pub size: u64,
+    /// Host descriptor backing the region (local mode only).
+    pub fd: RawFd,
+}
+
+/// Everything the target needs to reconstruct the domain.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct MigrationPayload {
+    pub mode: MigrationMode,
+    pub config: VMConfig,
+    /// Raw libvirt domain XML of the source domain.
+    pub domain_xml: String,
+    /// PCI addresses of GPU passthrough devices assigned to the domain, each
+    /// with the IOMMU group it belongs to on the source host.
+    pub gpu_assignment: Vec<GpuAssignment>,
+    /// Slot/size descriptors of the guest memory regions. Descriptors are
+    /// carried out of band, so only the metadata travels in the payload.
+    pub regions: Vec<RegionMeta>,
+}
+
+/// A GPU passthrough device assignment carried in the migration payload.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct GpuAssignment {
+    pub pci_address: String,
+    pub iommu_group: u32,
+}
+
+/// Serializable metadata for a [`MemoryRegion`] (the FD does not travel here).
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RegionMeta {
+    pub slot: u32,
+    pub size: u64,
+}
+
+/// Framed control messages exchanged over the migration channel.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+enum Frame {
+    Config(Box<MigrationPayload>),
+    ConfigAck,
+    MemoryRegions,
+    RegionsAck,
+    State(Vec<u8>),
+    StateAck,
+    Complete,
+    CompleteAck,
+    /// Sent by the receiver when it refuses the migration (e.g. a passthrough
+    /// GPU is busy in its IOMMU groups). The source aborts and keeps running.
+    Abort(String),
+}
+
+#[derive(Debug, Error)]
+pub enum MigrationError {
+    #[error("io error during migration: {0}")]
+    Io(#[from] std::io::Error),
+    #[error("failed to (de)serialize migration frame: {0}")]
+    Codec(#[from] bincode::Error),
+    #[error("unexpected frame, wanted {expected}")]
+    UnexpectedFrame { expected: &'static str },
+    #[error("target rejected migration: {0}")]
+    Rejected(String),
+    #[error("gpu {pci_address} (iommu group {iommu_group}) is not free on the target")]
+    GpuBusy { pci_address: String, iommu_group: u32 },
+    #[error("failed to pass memory descriptor for slot {slot}")]
+    FdHandoff { slot: u32 },
+}
+
+type Result<T> = std::result::Result<T, MigrationError>;
+
+/// Drives both sides of a live migration.
+pub struct MigrationManager {
+    libvirt: LibvirtManager,
+}
+
+impl MigrationManager {
+    pub fn new(libvirt: LibvirtManager) -> Self {
+        Self { libvirt }
+    }
+
+    /// Send a running domain to `endpoint`.
+    ///
+    /// `endpoint` is a Unix socket path for [`MigrationMode::LocalFdHandoff`] or
+    /// a `host:port` address for [`MigrationMode::CrossHostStream`].
+    pub async fn send(&self, vm_id: &str, mode: MigrationMode, endpoint: &str) -> Result<()> {
+        let domain = self.libvirt.lookup_domain(vm_id).map_err(to_io)?;
+        let domain_xml = domain.get_xml_desc(0).map_err(to_io)?;
+        let config = self.libvirt.config_for(vm_id).map_err(to_io)?;
+        let gpu_assignment = self.libvirt.gpu_assignment(vm_id).map_err(to_io)?;
+        let regions = self.libvirt.memory_regions(vm_id).map_err(to_io)?;
+
+        let payload = MigrationPayload {
+            mode,
+            config,
+            domain_xml,
+            gpu_assignment,
+            regions: regions
+                .iter()
+                .map(|r| RegionMeta { slot: r.slot, size: r.size })
+                .collect(),
+        };
+
+        match mode {
+            MigrationMode::LocalFdHandoff => {
+                let stream = UnixStream::connect(endpoint).await?;
+                self.send_local(stream, payload, &regions).await?;
+            }
+            MigrationMode::CrossHostStream => {
+                let stream = TcpStream::connect(endpoint).await?;
+                self.send_cross_host(stream, payload, &regions).await?;
+            }
+        }
+
+        // The domain has been handed off; stop tracking it locally.
+        info!(vm_id, ?mode, "migration send completed, releasing local domain");
+        self.libvirt.detach_migrated(vm_id).map_err(to_io)?;
+        Ok(())
+    }
+
+    async fn send_local(
+        &self,
+        mut stream: UnixStream,
+        payload: MigrationPayload,
+        regions: &[MemoryRegion],
+    ) -> Result<()> {
+        write_frame(&mut stream, &Frame::Config(Box::new(payload))).await?;
+        expect_config_ack(&mut stream).await?;
+
+        // Hand each region's descriptor to the target, tagged with its slot, so
+        // the receiver can install them at the right offsets without copying.
+        write_frame(&mut stream, &Frame::MemoryRegions).await?;
+        for region in regions {
+            send_fd(&stream, region.slot, region.fd).await?;
+        }
+        expect_frame(&mut stream, "RegionsAck", |f| matches!(f, Frame::RegionsAck)).await?;
+
+        let state = self.libvirt.save_device_state(regions).map_err(to_io)?;
+        write_frame(&mut stream, &Frame::State(state)).await?;
+        expect_frame(&mut stream, "StateAck", |f| matches!(f, Frame::StateAck)).await?;
+
+        write_frame(&mut stream, &Frame::Complete).await?;
+        expect_frame(&mut stream, "CompleteAck", |f| matches!(f, Frame::CompleteAck)).await?;
+        Ok(())
+    }
+
+    async fn send_cross_host(
+        &self,
+        mut stream: TcpStream,
+        payload: MigrationPayload,
+        regions: &[MemoryRegion],
+    ) -> Result<()> {
+        write_frame(&mut stream, &Frame::Config(Box::new(payload))).await?;
+        expect_config_ack(&mut stream).await?;
+
+        write_frame(&mut stream, &Frame::MemoryRegions).await?;
+        for region in regions {
+            // No descriptor passing across hosts: copy the pages over the wire.
+            let bytes = self.libvirt.read_region(region).map_err(to_io)?;
+            stream.write_u32(region.slot).await?;
+            stream.write_u64(region.size).await?;
+            stream.write_all(&bytes).await?;
+        }
+        expect_frame(&mut stream, "RegionsAck", |f| matches!(f, Frame::RegionsAck)).await?;
+
+        let state = self.libvirt.save_device_state(regions).map_err(to_io)?;
+        write_frame(&mut stream, &Frame::State(state)).await?;
+        expect_frame(&mut stream, "StateAck", |f| matches!(f, Frame::StateAck)).await?;
+
+        write_frame(&mut stream, &Frame::Complete).await?;
+        expect_frame(&mut stream, "CompleteAck", |f| matches!(f, Frame::CompleteAck)).await?;
+        Ok(())
+    }
+
+    /// Receive a migrating domain arriving on an already-accepted local socket.
+    pub async fn receive_local(
+        &self,
+        mut stream: UnixStream,
+        gpu_manager: &mut GPUManager,
+    ) -> Result<String> {
+        let payload = match read_frame(&mut stream).await? {
+            Frame::Config(p) => *p,
+            _ => return Err(MigrationError::UnexpectedFrame { expected: "Config" }),
+        };
+
+        // Refuse the handoff unless every passthrough GPU is free here.
+        if let Err(e) = self.validate_gpus(gpu_manager, &payload.gpu_assignment) {
+            let reason = e.to_string();
+            write_frame(&mut stream, &Frame::Abort(reason.clone())).await?;
+            warn!(%reason, "aborting inbound migration: gpu not free on target");
+            return Err(e);
+        }
+        write_frame(&mut stream, &Frame::ConfigAck).await?;
+
+        expect_frame(&mut stream, "MemoryRegions", |f| matches!(f, Frame::MemoryRegions)).await?;
+        let mut regions = Vec::with_capacity(payload.regions.len());
+        for meta in &payload.regions {
+            let (slot, fd) = recv_fd(&stream).await?;
+            regions.push(MemoryRegion { slot, size: meta.size, fd });
+        }
+        self.libvirt.install_regions(&regions).map_err(to_io)?;
+        write_frame(&mut stream, &Frame::RegionsAck).await?;
+
+        let state = match read_frame(&mut stream).await? {
+            Frame::State(s) => s,
+            _ => return Err(MigrationError::UnexpectedFrame { expected: "State" }),
+        };
+        let vm_id = self
+            .libvirt
+            .reconstruct_domain(&payload.config, &payload.domain_xml, &regions, &state)
+            .map_err(to_io)?;
+        write_frame(&mut stream, &Frame::StateAck).await?;
+
+        expect_frame(&mut stream, "Complete", |f| matches!(f, Frame::Complete)).await?;
+        write_frame(&mut stream, &Frame::CompleteAck).await?;
+        info!(vm_id, "inbound local migration committed");
+        Ok(vm_id)
+    }
+
+    /// Receive a migrating domain arriving over a cross-host TCP connection.
+    ///
+    /// The mirror of [`send_cross_host`](Self::send_cross_host): the guest RAM
+    /// is streamed inline (no descriptor passing across hosts), each region is
+    /// restored into local backing store, and the domain is reconstructed from
+    /// the config, XML, and device state.
+    pub async fn receive_cross_host(
+        &self,
+        mut stream: TcpStream,
+        gpu_manager: &mut GPUManager,
+    ) -> Result<String> {
+        let payload = match read_frame(&mut stream).await? {
+            Frame::Config(p) => *p,
+            _ => return Err(MigrationError::UnexpectedFrame { expected: "Config" }),
+        };
+
+        // Refuse the handoff unless every passthrough GPU is free here.
+        if let Err(e) = self.validate_gpus(gpu_manager, &payload.gpu_assignment) {
+            let reason = e.to_string();
+            write_frame(&mut stream, &Frame::Abort(reason.clone())).await?;
+            warn!(%reason, "aborting inbound migration: gpu not free on target");
+            return Err(e);
+        }
+        write_frame(&mut stream, &Frame::ConfigAck).await?;
+
+        expect_frame(&mut stream, "MemoryRegions", |f| matches!(f, Frame::MemoryRegions)).await?;
+        let mut regions = Vec::with_capacity(payload.regions.len());
+        for _ in &payload.regions {
+            let slot = stream.read_u32().await?;
+            let size = stream.read_u64().await?;
+            let mut bytes = vec![0u8; size as usize];
+            stream.read_exact(&mut bytes).await?;
+            let region = self.libvirt.restore_region(slot, &bytes).map_err(to_io)?;
+            regions.push(region);
+        }
+        write_frame(&mut stream, &Frame::RegionsAck).await?;
+
+        let state = match read_frame(&mut stream).await? {
+            Frame::State(s) => s,
+            _ => return Err(MigrationError::UnexpectedFrame { expected: "State" }),
+        };
+        let vm_id = self
+            .libvirt
+            .reconstruct_domain(&payload.config, &payload.domain_xml, &regions, &state)
+            .map_err(to_io)?;
+        write_frame(&mut stream, &Frame::StateAck).await?;
+
+        expect_frame(&mut stream, "Complete", |f| matches!(f, Frame::Complete)).await?;
+        write_frame(&mut stream, &Frame::CompleteAck).await?;
+        info!(vm_id, "inbound cross-host migration committed");
+        Ok(vm_id)
+    }
+
+    /// Verify each requested passthrough GPU is free in this host's IOMMU groups.
+    fn validate_gpus(&self, gpu_manager: &mut GPUManager, assignment: &[GpuAssignment]) -> Result<()> {
+        for gpu in assignment {
+            let free = gpu_manager.is_iommu_group_free(gpu.iommu_group).map_err(to_io)?;
+            if !free {
+                return Err(MigrationError::GpuBusy {
+                    pci_address: gpu.pci_address.clone(),
+                    iommu_group: gpu.iommu_group,
+                });
+            }
+        }
+        Ok(())
+    }
+}
+
+fn to_io(err: impl std::fmt::Display) -> MigrationError {
+    MigrationError::Io(std::io::Error::new(std::io::ErrorKind::Other, err.to_string()))
+}
+
+async fn write_frame<S>(stream: &mut S, frame: &Frame) -> Result<()>
+where
+    S: AsyncWriteExt + Unpin,
+{
+    let bytes = bincode::serialize(frame)?;
+    stream.write_u32(bytes.len() as u32).await?;
+    stream.write_all(&bytes).await?;
+    stream.flush().await?;
+    Ok(())
+}
+
+async fn read_frame<S>(stream: &mut S) -> Result<Frame>
+where
+    S: AsyncReadExt + Unpin,
+{
+    let len = stream.read_u32().await? as usize;
+    let mut buf = vec![0u8; len];
+    stream.read_exact(&mut buf).await?;
+    Ok(bincode::deserialize(&buf)?)
+}
+
+async fn expect_frame<S>(
+    stream: &mut S,
+    expected: &'static str,
+    pred: impl Fn(&Frame) -> bool,
+) -> Result<()>
+where
+    S: AsyncReadExt + Unpin,
+{
+    match read_frame(stream).await? {
+        Frame::Abort(reason) => Err(MigrationError::Rejected(reason)),
+        frame if pred(&frame) => Ok(()),
+        _ => Err(MigrationError::UnexpectedFrame { expected }),
+    }
+}
+
+async fn expect_config_ack<S>(stream: &mut S) -> Result<()>
+where
+    S: AsyncReadExt + Unpin,
+{
+    expect_frame(stream, "ConfigAck", |f| matches!(f, Frame::ConfigAck)).await
+}
+
+/// Send a single descriptor plus its slot index over `stream` as a `SCM_RIGHTS`
+/// ancillary message. The slot travels in the normal data portion so the
+/// receiver can pair each descriptor with the memory slot it backs.
+///
+/// The socket is a non-blocking tokio `UnixStream`, so the raw `sendmsg` is
+/// driven through tokio's write readiness: we wait for writability and retry on
+/// `WouldBlock` rather than erroring out on `EAGAIN`.
+async fn send_fd(stream: &UnixStream, slot: u32, fd: RawFd) -> Result<()> {
+    use std::os::unix::io::AsRawFd;
+
+    use nix::sys::socket::{sendmsg, ControlMessage, MsgFlags};
+    use std::io::IoSlice;
+    use tokio::io::Interest;
+
+    let slot_bytes = slot.to_be_bytes();
+    let fds = [fd];
+    loop {
+        stream.writable().await?;
+        let res = stream.try_io(Interest::WRITABLE, || {
+            let iov = [IoSlice::new(&slot_bytes)];
+            let cmsg = [ControlMessage::ScmRights(&fds)];
+            sendmsg::<()>(stream.as_raw_fd(), &iov, &cmsg, MsgFlags::empty(), None)
+                .map_err(|e| std::io::Error::from_raw_os_error(e as i32))
+        });
+        match res {
+            Ok(_) => return Ok(()),
+            Err(e) if e.kind() == std::io::ErrorKind::WouldBlock => continue,
+            Err(_) => return Err(MigrationError::FdHandoff { slot }),
+        }
+    }
+}
+
+/// Receive a single `(slot, fd)` pair sent by [`send_fd`].
+///
+/// Like [`send_fd`], the raw `recvmsg` is gated on tokio's read readiness so a
+/// descriptor that has not arrived yet surfaces as `WouldBlock` and is retried
+/// after the next readable event instead of failing the migration.
+async fn recv_fd(stream: &UnixStream) -> Result<(u32, RawFd)> {
+    use std::os::unix::io::AsRawFd;
+
+    use nix::cmsg_space;
+    use nix::sys::socket::{recvmsg, ControlMessageOwned, MsgFlags};
+    use std::io::IoSliceMut;
+    use tokio::io::Interest;
+
+    let mut slot_bytes = [0u8; 4];
+    loop {
+        stream.readable().await?;
+        let mut cmsg_buf = cmsg_space!(RawFd);
+        let res = stream.try_io(Interest::READABLE, || {
+            let mut iov = [IoSliceMut::new(&mut slot_bytes)];
+            recvmsg::<()>(stream.as_raw_fd(), &mut iov, Some(&mut cmsg_buf), MsgFlags::empty())
+                .map(|msg| msg.cmsgs().collect::<Vec<_>>())
+                .map_err(|e| std::io::Error::from_raw_os_error(e as i32))
+        });
+        let cmsgs = match res {
+            Ok(cmsgs) => cmsgs,
+            Err(e) if e.kind() == std::io::ErrorKind::WouldBlock => continue,
+            Err(e) => return Err(to_io(e)),
+        };
+
+        let slot = u32::from_be_bytes(slot_bytes);
+        for cmsg in cmsgs {
+            if let ControlMessageOwned::ScmRights(fds) = cmsg {
+                if let Some(&fd) = fds.first() {
+                    return Ok((slot, fd));
+                }
+            }
+        }
+        return Err(MigrationError::FdHandoff { slot });
+    }
+}
+
+/// Accept inbound local-mode migrations on `path`.
+pub async fn serve_local(path: PathBuf) -> Result<tokio::net::UnixListener> {
+    let _ = std::fs::remove_file(&path);
+    Ok(tokio::net::UnixListener::bind(path)?)
+}
+
+/// Accept inbound cross-host migrations on `addr` (a `host:port` bind address).
+pub async fn serve_cross_host(addr: &str) -> Result<tokio::net::TcpListener> {
+    Ok(tokio::net::TcpListener::bind(addr).await?)
+}
@@ -0,0 +1,11 @@
+pub mod libvirt;
+pub mod vm;
+pub mod errors;
+pub mod resource_manager;
+pub mod migration;
+pub mod snapshot;
+pub mod console;
+pub mod hotplug;
+pub mod events;
+
+pub use libvirt::LibvirtManager;
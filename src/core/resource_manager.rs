@@ -1,16 +1,390 @@
+//! Per-user resource accounting and quota enforcement.
+//!
+//! Every VM a user runs reserves vCPUs, memory, disk, and (optionally) a slice
+//! of a GPU. [`ResourceManager`] keeps a persisted table of those reservations
+//! so limits survive a manager restart, refuses `create_vm`/`resize` requests
+//! that would push a user over their configured limits, and releases a VM's
+//! reservation when it is deleted.
+//!
+//! GPUs are shared fractionally: a single physical device can be split into
+//! fractional slices across several VMs instead of being handed out as an
+//! exclusive passthrough. Each slice is one fraction of the whole device;
+//! memory and compute are not accounted separately. The manager tracks how
+//! much of each device is committed and reports allocated vs. free fractions
+//! to callers.
+
+use std::collections::HashMap;
+use std::path::PathBuf;
+
+use serde::{Deserialize, Serialize};
+
 use crate::core::vm::VMConfig;
 use crate::core::errors::GpuShareError;
 
+/// Where the reservation table is persisted.
+const QUOTA_STORE: &str = "/var/lib/gpu-share/quota.json";
+
+/// Per-user ceiling. A user without an explicit entry gets [`QuotaLimits::default`].
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+pub struct QuotaLimits {
+    pub max_vcpus: u32,
+    pub max_memory_kb: u64,
+    pub max_disk_gb: u64,
+    /// Total GPU fraction summed across all of the user's VMs (1.0 == one GPU).
+    pub max_gpu_fraction: f64,
+}
+
+impl Default for QuotaLimits {
+    fn default() -> Self {
+        Self {
+            max_vcpus: 32,
+            max_memory_kb: 64 * 1024 * 1024,
+            max_disk_gb: 1024,
+            max_gpu_fraction: 2.0,
+        }
+    }
+}
+
+/// A single VM's reservation.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct Reservation {
+    user: String,
+    vcpus: u32,
+    memory_kb: u64,
+    disk_gb: u64,
+}
+
+/// A fractional GPU allocation tied to a VM.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct GpuReservation {
+    user: String,
+    gpu_id: String,
+    fraction: f64,
+}
+
+/// Allocated vs. free capacity for one physical GPU.
+#[derive(Debug, Clone, Serialize)]
+pub struct GpuAllocation {
+    pub gpu_id: String,
+    pub allocated_fraction: f64,
+    pub free_fraction: f64,
+}
+
+/// The persisted part of the manager's state.
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct QuotaTable {
+    #[serde(default)]
+    limits: HashMap<String, QuotaLimits>,
+    #[serde(default)]
+    reservations: HashMap<String, Reservation>,
+    /// GPU slices held by each VM, keyed by `vm_id`. A VM can hold slices of
+    /// several distinct devices, so each entry is a list (at most one per
+    /// `gpu_id`).
+    #[serde(default)]
+    gpu_reservations: HashMap<String, Vec<GpuReservation>>,
+}
+
 #[derive(Debug)]
-pub struct ResourceManager;
+pub struct ResourceManager {
+    table: QuotaTable,
+    store: PathBuf,
+}
+
+impl Default for ResourceManager {
+    fn default() -> Self {
+        Self::new()
+    }
+}
 
 impl ResourceManager {
+    /// Load the persisted reservation table, or start empty if none exists.
     pub fn new() -> Self {
-        Self
+        let store = PathBuf::from(QUOTA_STORE);
+        let table = std::fs::read(&store)
+            .ok()
+            .and_then(|bytes| serde_json::from_slice(&bytes).ok())
+            .unwrap_or_default();
+        Self { table, store }
+    }
+
+    fn limits_for(&self, user: &str) -> QuotaLimits {
+        self.table.limits.get(user).copied().unwrap_or_default()
     }
 
-    pub fn check_quota(&self, _user: &str, _config: &VMConfig) -> Result<(), GpuShareError> {
-        // Implement actual quota checks here
+    /// Current vCPU/memory/disk usage for `user`, excluding the reservation
+    /// named `exclude` (used so a resize does not count a VM against itself).
+    fn usage(&self, user: &str, exclude: Option<&str>) -> (u32, u64, u64) {
+        let mut vcpus = 0;
+        let mut memory_kb = 0;
+        let mut disk_gb = 0;
+        for (id, r) in &self.table.reservations {
+            if r.user == user && Some(id.as_str()) != exclude {
+                vcpus += r.vcpus;
+                memory_kb += r.memory_kb;
+                disk_gb += r.disk_gb;
+            }
+        }
+        (vcpus, memory_kb, disk_gb)
+    }
+
+    /// Check whether committing `config` for `user` stays within quota.
+    ///
+    /// This is the additive check wired into `create_vm` and `resize`: it sums
+    /// the user's existing reservations (minus `vm_id`, so a resize does not
+    /// count a VM against itself) with the requested config and compares against
+    /// the user's limits. Pass `None` for `vm_id` when the VM does not exist yet
+    /// (create), or `Some(vm_id)` with the reservation key (the domain UUID) to
+    /// exclude it (resize).
+    pub fn check_quota(
+        &self,
+        user: &str,
+        vm_id: Option<&str>,
+        config: &VMConfig,
+    ) -> Result<(), GpuShareError> {
+        let limits = self.limits_for(user);
+        let (vcpus, memory_kb, disk_gb) = self.usage(user, vm_id);
+
+        if vcpus + config.vcpus > limits.max_vcpus {
+            return Err(quota_exceeded("vCPU", (vcpus + config.vcpus) as u64, limits.max_vcpus as u64));
+        }
+        if memory_kb + config.memory_kb > limits.max_memory_kb {
+            return Err(quota_exceeded("memory (KiB)", memory_kb + config.memory_kb, limits.max_memory_kb));
+        }
+        if disk_gb + config.disk_size_gb > limits.max_disk_gb {
+            return Err(quota_exceeded("disk (GiB)", disk_gb + config.disk_size_gb, limits.max_disk_gb));
+        }
+        Ok(())
+    }
+
+    /// Record a VM's reservation after its quota check has passed.
+    pub fn reserve(&mut self, user: &str, vm_id: &str, config: &VMConfig) -> Result<(), GpuShareError> {
+        self.check_quota(user, Some(vm_id), config)?;
+        self.table.reservations.insert(
+            vm_id.to_string(),
+            Reservation {
+                user: user.to_string(),
+                vcpus: config.vcpus,
+                memory_kb: config.memory_kb,
+                disk_gb: config.disk_size_gb,
+            },
+        );
+        self.persist();
         Ok(())
     }
-} 
\ No newline at end of file
+
+    /// Rewrite a VM's vCPU/memory reservation after a live resize, preserving
+    /// its existing disk reservation. The new totals must already have passed
+    /// [`check_quota`](Self::check_quota). Records a reservation if the VM had
+    /// none yet (e.g. one created before accounting was enabled).
+    pub fn update_resize(&mut self, user: &str, vm_id: &str, vcpus: u32, memory_kb: u64) {
+        let disk_gb = self.table.reservations.get(vm_id).map(|r| r.disk_gb).unwrap_or(0);
+        self.table.reservations.insert(
+            vm_id.to_string(),
+            Reservation { user: user.to_string(), vcpus, memory_kb, disk_gb },
+        );
+        self.persist();
+    }
+
+    /// Reserve `fraction` of GPU `gpu_id` for `vm_id`, honouring both the
+    /// device's remaining capacity and the user's GPU quota.
+    pub fn reserve_gpu(
+        &mut self,
+        user: &str,
+        vm_id: &str,
+        gpu_id: &str,
+        fraction: f64,
+    ) -> Result<(), GpuShareError> {
+        if !(0.0..=1.0).contains(&fraction) || fraction == 0.0 {
+            return Err(GpuShareError::InvalidConfig(format!(
+                "gpu fraction {fraction} must be in (0.0, 1.0]"
+            )));
+        }
+        // A VM re-reserving the same device updates its existing slice, so
+        // exclude that slice from both ceilings rather than double-counting it.
+        let device_allocated = self.device_allocated_excluding(gpu_id, Some(vm_id));
+        if device_allocated + fraction > 1.0 + f64::EPSILON {
+            return Err(quota_exceeded_f("GPU device capacity", device_allocated + fraction, 1.0));
+        }
+        let limits = self.limits_for(user);
+        let user_gpu = self.user_gpu_excluding(user, vm_id, gpu_id);
+        if user_gpu + fraction > limits.max_gpu_fraction + f64::EPSILON {
+            return Err(quota_exceeded_f("GPU fraction", user_gpu + fraction, limits.max_gpu_fraction));
+        }
+        let slices = self.table.gpu_reservations.entry(vm_id.to_string()).or_default();
+        slices.retain(|g| g.gpu_id != gpu_id);
+        slices.push(GpuReservation { user: user.to_string(), gpu_id: gpu_id.to_string(), fraction });
+        self.persist();
+        Ok(())
+    }
+
+    /// Release every reservation held by `vm_id`.
+    pub fn release(&mut self, vm_id: &str) {
+        let had_cpu = self.table.reservations.remove(vm_id).is_some();
+        let had_gpu = self.table.gpu_reservations.remove(vm_id).is_some();
+        if had_cpu || had_gpu {
+            self.persist();
+        }
+    }
+
+    /// Release every GPU slice held by `vm_id`, leaving its CPU/memory
+    /// reservation intact (used to roll back a failed `attach_gpu`).
+    pub fn release_gpu(&mut self, vm_id: &str) {
+        if self.table.gpu_reservations.remove(vm_id).is_some() {
+            self.persist();
+        }
+    }
+
+    /// Fraction of `gpu_id` currently committed across all VMs.
+    fn device_allocated(&self, gpu_id: &str) -> f64 {
+        self.device_allocated_excluding(gpu_id, None)
+    }
+
+    /// Like [`device_allocated`](Self::device_allocated) but ignoring any slice
+    /// of `gpu_id` held by `exclude`'s VM.
+    fn device_allocated_excluding(&self, gpu_id: &str, exclude: Option<&str>) -> f64 {
+        self.table
+            .gpu_reservations
+            .iter()
+            .filter(|(vm, _)| Some(vm.as_str()) != exclude)
+            .flat_map(|(_, slices)| slices.iter())
+            .filter(|g| g.gpu_id == gpu_id)
+            .map(|g| g.fraction)
+            .sum()
+    }
+
+    /// Total GPU fraction committed to `user`, excluding the single slice the
+    /// VM `vm_id` already holds of `gpu_id` (the one being re-reserved).
+    fn user_gpu_excluding(&self, user: &str, vm_id: &str, gpu_id: &str) -> f64 {
+        let mut total = 0.0;
+        for (vm, slices) in &self.table.gpu_reservations {
+            for g in slices {
+                if g.user != user {
+                    continue;
+                }
+                if vm == vm_id && g.gpu_id == gpu_id {
+                    continue;
+                }
+                total += g.fraction;
+            }
+        }
+        total
+    }
+
+    /// Allocated vs. free fractions for each GPU id in `gpu_ids`.
+    pub fn gpu_allocations(&self, gpu_ids: &[String]) -> Vec<GpuAllocation> {
+        gpu_ids
+            .iter()
+            .map(|id| {
+                let allocated = self.device_allocated(id);
+                GpuAllocation {
+                    gpu_id: id.clone(),
+                    allocated_fraction: allocated,
+                    free_fraction: (1.0 - allocated).max(0.0),
+                }
+            })
+            .collect()
+    }
+
+    fn persist(&self) {
+        if let Some(parent) = self.store.parent() {
+            let _ = std::fs::create_dir_all(parent);
+        }
+        if let Ok(bytes) = serde_json::to_vec_pretty(&self.table) {
+            let _ = std::fs::write(&self.store, bytes);
+        }
+    }
+}
+
+fn quota_exceeded(resource: &str, requested: u64, limit: u64) -> GpuShareError {
+    GpuShareError::QuotaExceeded(format!(
+        "{resource} request of {requested} exceeds quota of {limit}"
+    ))
+}
+
+fn quota_exceeded_f(resource: &str, requested: f64, limit: f64) -> GpuShareError {
+    GpuShareError::QuotaExceeded(format!(
+        "{resource} request of {requested:.2} exceeds quota of {limit:.2}"
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A manager backed by a throwaway store path so `persist` has somewhere to
+    /// write without touching the real `/var/lib` location.
+    fn manager(tag: &str) -> ResourceManager {
+        let store = std::env::temp_dir().join(format!("gpu-share-quota-{tag}.json"));
+        let _ = std::fs::remove_file(&store);
+        ResourceManager { table: QuotaTable::default(), store }
+    }
+
+    fn config(name: &str, vcpus: u32, memory_kb: u64, disk_gb: u64) -> VMConfig {
+        VMConfig {
+            name: name.to_string(),
+            memory_kb,
+            vcpus,
+            disk_path: PathBuf::from(format!("/tmp/{name}.qcow2")),
+            disk_size_gb: disk_gb,
+        }
+    }
+
+    #[test]
+    fn quota_sums_across_vms_and_rejects_overage() {
+        let mut rm = manager("sums");
+        rm.reserve("alice", "vm-a", &config("a", 20, 0, 0)).unwrap();
+
+        // 20 already committed, +12 fits the default 32-vCPU ceiling.
+        assert!(rm.check_quota("alice", None, &config("b", 12, 0, 0)).is_ok());
+        // +13 would total 33 and must be rejected.
+        assert!(rm.check_quota("alice", None, &config("b", 13, 0, 0)).is_err());
+    }
+
+    #[test]
+    fn resize_does_not_count_a_vm_against_itself() {
+        let mut rm = manager("resize");
+        rm.reserve("bob", "vm-x", &config("x", 2, 0, 0)).unwrap();
+
+        // Resizing vm-x up to 30 excludes its own recorded 2 vCPUs.
+        assert!(rm.check_quota("bob", Some("vm-x"), &config("x", 30, 0, 0)).is_ok());
+        // Without the exclusion the VM's own 2 would be double-counted (2+31>32).
+        assert!(rm.check_quota("bob", Some("vm-x"), &config("x", 31, 0, 0)).is_ok());
+        assert!(rm.check_quota("bob", None, &config("x", 31, 0, 0)).is_err());
+    }
+
+    #[test]
+    fn gpu_slices_are_keyed_per_device() {
+        let mut rm = manager("gpu-multi");
+        rm.reserve_gpu("carol", "vm-1", "gpu-0", 0.5).unwrap();
+        rm.reserve_gpu("carol", "vm-1", "gpu-1", 0.5).unwrap();
+
+        // Both slices are accounted, not overwritten.
+        assert_eq!(rm.device_allocated("gpu-0"), 0.5);
+        assert_eq!(rm.device_allocated("gpu-1"), 0.5);
+        assert_eq!(rm.user_gpu_excluding("carol", "none", "none"), 1.0);
+
+        // Releasing the VM frees every slice it held.
+        rm.release_gpu("vm-1");
+        assert_eq!(rm.device_allocated("gpu-0"), 0.0);
+        assert_eq!(rm.device_allocated("gpu-1"), 0.0);
+    }
+
+    #[test]
+    fn gpu_device_capacity_is_enforced_across_vms() {
+        let mut rm = manager("gpu-cap");
+        rm.reserve_gpu("dave", "vm-1", "gpu-0", 0.6).unwrap();
+        // A second VM cannot claim 0.5 of a device with only 0.4 free.
+        assert!(rm.reserve_gpu("dave", "vm-2", "gpu-0", 0.5).is_err());
+        // But 0.4 fits exactly.
+        assert!(rm.reserve_gpu("dave", "vm-2", "gpu-0", 0.4).is_ok());
+        assert_eq!(rm.device_allocated("gpu-0"), 1.0);
+    }
+
+    #[test]
+    fn re_reserving_same_device_replaces_rather_than_stacks() {
+        let mut rm = manager("gpu-replace");
+        rm.reserve_gpu("erin", "vm-1", "gpu-0", 0.3).unwrap();
+        rm.reserve_gpu("erin", "vm-1", "gpu-0", 0.7).unwrap();
+        assert_eq!(rm.device_allocated("gpu-0"), 0.7);
+    }
+}
@@ -20,19 +20,31 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
     // Initialize core components
     let libvirt = Arc::new(Mutex::new(core::LibvirtManager::new()?));
     let gpu_manager = Arc::new(Mutex::new(gpu::GPUManager::new()?));
-    let metrics = Arc::new(Mutex::new(monitoring::MetricsCollector::new(
-        5, // 5 second collection interval
-        24, // 24 hour retention
-    )));
+    // The event bus is shared with the metrics collector so it can publish
+    // threshold-crossing events alongside the handler-driven lifecycle events.
+    let events = Arc::new(core::events::EventBus::new());
+    let metrics = Arc::new(Mutex::new(
+        monitoring::MetricsCollector::new(
+            5, // 5 second collection interval
+            24, // 24 hour retention
+        )
+        .with_event_bus(events.clone()),
+    ));
 
     // Shutdown mechanism for graceful shutdown
     let (shutdown_sender, shutdown_receiver) = oneshot::channel();
 
     // Initialize application state
+    let consoles = Arc::new(Mutex::new(core::console::ConsoleManager::new()));
+    let resources = Arc::new(Mutex::new(core::resource_manager::ResourceManager::new()));
+
     let state = Arc::new(api::AppState {
         libvirt,
         gpu_manager,
         metrics,
+        consoles,
+        resources,
+        events,
         shutdown_signal: Arc::new(Mutex::new(shutdown_sender)),
         shutdown_receiver: Arc::new(Mutex::new(shutdown_receiver)),
     });